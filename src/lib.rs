@@ -0,0 +1,455 @@
+// Djimon Nowak
+
+//! Core station-aggregation logic, reusable outside of the `sci_comp_extra1`
+//! binary (benchmark harnesses, embedded/`no_std` callers, etc).
+//!
+//! This crate's default build pulls in `std` so the binary target keeps
+//! working unmodified; disable default features (`--no-default-features`) to
+//! build the pure `no_std` + `alloc` aggregation path, which depends only on
+//! `alloc`, `hashbrown`, `rustc-hash`, `memchr`, `fast-float` and `libm`.
+//! Everything that needs a filesystem, threads, or stdout -- `clap`/`Args`,
+//! mmap'ing, file reading, result printing -- lives behind the `cli` feature
+//! in the binary instead of in this library.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::hash::BuildHasherDefault;
+use hashbrown::HashMap;
+use memchr::memchr;
+use rustc_hash::FxHasher;
+
+// Station names are hashed once per record on the hottest path in the
+// program, so the default (DoS-resistant but slower) `ahash` hashbrown picks
+// isn't worth paying for here; `FxHash` is the same fast, non-cryptographic
+// hash `main.rs` used before this aggregation logic moved into the library.
+type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+// `core` has no `round`/`floor`/`ceil` for `f64` -- those live behind `std`
+// (which pulls in the platform's libm) or, without `std`, the `libm` crate.
+#[cfg(feature = "std")]
+mod float_ops {
+    pub fn round(v: f64) -> f64 {
+        v.round()
+    }
+    pub fn floor(v: f64) -> f64 {
+        v.floor()
+    }
+    pub fn ceil(v: f64) -> f64 {
+        v.ceil()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod float_ops {
+    pub fn round(v: f64) -> f64 {
+        libm::round(v)
+    }
+    pub fn floor(v: f64) -> f64 {
+        libm::floor(v)
+    }
+    pub fn ceil(v: f64) -> f64 {
+        libm::ceil(v)
+    }
+}
+
+/// Owned-key result map: station name -> aggregated histogram. Keys are
+/// boxed byte slices rather than `&str` because record chunks (streamed
+/// reads, mmap sub-slices) don't all outlive a single shared buffer.
+pub type Map = HashMap<Box<[u8]>, StationValues, FxBuildHasher>;
+
+/// Sink for raw records that fail parsing/range validation under lenient
+/// mode. Kept as a trait rather than a concrete file handle so the pure
+/// aggregation path has no filesystem dependency; the `cli` feature in the
+/// binary provides the file-backed implementation. `Sync` is required
+/// because a single sink is shared by reference across worker threads.
+pub trait QuarantineSink: Sync {
+    fn record(&self, bad: &[u8]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    Truncated,
+    MissingSeparator,
+    InvalidUtf8Name,
+    ParseError,
+    OutOfRange,
+}
+
+impl FailureKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FailureKind::Truncated => "truncated",
+            FailureKind::MissingSeparator => "missing_separator",
+            FailureKind::InvalidUtf8Name => "invalid_utf8_name",
+            FailureKind::ParseError => "parse_error",
+            FailureKind::OutOfRange => "out_of_range",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ParseStats {
+    pub lines_read: u64,
+    pub lines_skipped: u64,
+    pub failures: HashMap<FailureKind, u64>,
+}
+
+impl ParseStats {
+    fn record_failure(&mut self, kind: FailureKind) {
+        self.lines_skipped += 1;
+        *self.failures.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &ParseStats) {
+        self.lines_read += other.lines_read;
+        self.lines_skipped += other.lines_skipped;
+        for (kind, count) in &other.failures {
+            *self.failures.entry(*kind).or_insert(0) += count;
+        }
+    }
+}
+
+// A value is bucketed by its rounded tenths-of-a-degree representation, so
+// `-12.3` and `-12.34` land in the same bucket as `-12.3`.
+fn value_to_tenths(value: f64) -> Option<i32> {
+    if !value.is_finite() {
+        return None;
+    }
+    let scaled = float_ops::round(value * 10.0);
+    if scaled < i32::MIN as f64 || scaled > i32::MAX as f64 {
+        return None;
+    }
+    Some(scaled as i32)
+}
+
+fn tenths_to_value(tenths: i32) -> f64 {
+    tenths as f64 * 0.1
+}
+
+pub fn round_off(value: f64) -> f64 {
+    float_ops::round(value * 10.0) / 10.0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationValues {
+    pub min: f64,
+    pub max: f64,
+    // Sparse, grow-on-demand histogram: bucket (rounded tenths) -> count.
+    // Unlike a preallocated HashMap over a fixed range, this scales with the
+    // number of *distinct* values actually observed; the only limit on the
+    // domain is that a value's rounded tenths must fit in an `i32`.
+    pub frequency: BTreeMap<i32, u64>,
+    pub count: u64,
+}
+
+impl StationValues {
+    pub fn new() -> Self {
+        StationValues {
+            min: 0.0,
+            max: 0.0,
+            count: 0,
+            frequency: BTreeMap::new(),
+        }
+    }
+
+    pub fn new_with_value(value: f64) -> Self {
+        let mut station_values = StationValues::new();
+        station_values.min = value;
+        station_values.max = value;
+        let tenths = value_to_tenths(value).expect("Value is not finite");
+        *station_values.frequency.entry(tenths).or_insert(0) += 1;
+        station_values.count = 1;
+        station_values
+    }
+
+    // Returns true if `value` can be bucketed (i.e. is finite and its rounded
+    // tenths fit in an `i32`).
+    pub fn in_range(value: f64) -> bool {
+        value_to_tenths(value).is_some()
+    }
+
+    // get 0-indexed value by index, walking buckets in ascending key order
+    fn get_nth_value(&self, n: u64) -> f64 {
+        let mut cur = 0;
+
+        for (&key, &count) in &self.frequency {
+            if n < cur + count {
+                return tenths_to_value(key);
+            }
+            cur += count;
+        }
+
+        0.0
+    }
+
+    pub fn get_median(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        if self.count.is_multiple_of(2) {
+            // even number of values -> return 1/2(left-middle + right-middle)
+            let left_mid_index = (self.count / 2) - 1;
+            (self.get_nth_value(left_mid_index) + self.get_nth_value(left_mid_index + 1)) / 2.0
+        } else {
+            // odd number of values -> return middle
+            self.get_nth_value(self.count / 2)
+        }
+    }
+
+    // Arbitrary quantile `q` in [0, 1], reusing the cumulative-count walk from
+    // `get_nth_value`. Without `interpolate`, returns the value at rank
+    // `ceil(q * count) - 1`, clamped to `[0, count - 1]`. With `interpolate`
+    // set, takes position `q * (count - 1)` (clamped the same way) and
+    // linearly interpolates between the two adjacent buckets around it.
+    pub fn get_quantile(&self, q: f64, interpolate: bool) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        if !interpolate {
+            let rank = (float_ops::ceil(q * self.count as f64) as i64 - 1).clamp(0, self.count as i64 - 1);
+            return self.get_nth_value(rank as u64);
+        }
+
+        let pos = q * (self.count - 1) as f64;
+        let lower = float_ops::floor(pos).clamp(0.0, (self.count - 1) as f64) as u64;
+        let upper = float_ops::ceil(pos).clamp(0.0, (self.count - 1) as f64) as u64;
+        let lower_value = self.get_nth_value(lower);
+        if lower == upper {
+            return lower_value;
+        }
+        let upper_value = self.get_nth_value(upper);
+        let frac = pos - float_ops::floor(pos);
+        lower_value + (upper_value - lower_value) * frac
+    }
+
+    // Combine another partial histogram (e.g. from another worker) into this
+    // one: buckets are summed, counts are added, and min/max are widened.
+    pub fn merge(&mut self, other: &StationValues) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            if other.min < self.min {
+                self.min = other.min;
+            }
+            if other.max > self.max {
+                self.max = other.max;
+            }
+        }
+        for (&bucket, &count) in &other.frequency {
+            *self.frequency.entry(bucket).or_insert(0) += count;
+        }
+        self.count += other.count;
+    }
+}
+
+impl Default for StationValues {
+    fn default() -> Self {
+        StationValues::new()
+    }
+}
+
+fn write_bad_record(quarantine: Option<&dyn QuarantineSink>, record: &[u8]) {
+    if let Some(sink) = quarantine {
+        sink.record(record);
+    }
+}
+
+// Folds one more observation for an already-present station into its
+// histogram. Shared by the owned and borrowed-key scan loops below so the
+// two only differ in how they look a station up, not in how a hit updates
+// it.
+fn fold_value(existing: &mut StationValues, value: f64) {
+    if value < existing.min {
+        existing.min = value;
+    }
+    if value > existing.max {
+        existing.max = value;
+    }
+    *existing.frequency.entry(value_to_tenths(value).unwrap()).or_insert(0) += 1;
+    existing.count += 1;
+}
+
+/// Scans `data` for `;`-separated, newline-terminated records, calling
+/// `on_record(name, value)` for each one that parses and range-checks
+/// cleanly. This is the single place that knows the on-disk record format;
+/// [`process_chunk`] and [`process_chunk_borrowed`] are thin wrappers that
+/// only differ in how `on_record` files a station away (owned vs. borrowed
+/// key).
+fn scan_records<'a>(
+    data: &'a [u8],
+    lenient: bool,
+    quarantine: Option<&dyn QuarantineSink>,
+    mut on_record: impl FnMut(&'a [u8], f64),
+) -> ParseStats {
+    let mut stats = ParseStats::default();
+    let mut buffer = data;
+
+    loop {
+        // Find the line first, then look for `;` *within* it -- searching
+        // for `;` over the whole remaining buffer would let a malformed
+        // line with no separator absorb the following line (newline and
+        // all) into what looks like one long station name.
+        let end = match memchr(b'\n', buffer) {
+            Some(end) => end,
+            None => {
+                if !buffer.is_empty() {
+                    stats.record_failure(FailureKind::Truncated);
+                    if lenient {
+                        write_bad_record(quarantine, buffer);
+                    } else {
+                        panic!("Truncated record with no trailing newline");
+                    }
+                }
+                break;
+            }
+        };
+        let line = &buffer[..end];
+
+        let comma_seperator = match memchr(b';', line) {
+            Some(pos) => pos,
+            None => {
+                stats.record_failure(FailureKind::MissingSeparator);
+                if lenient {
+                    write_bad_record(quarantine, line);
+                    buffer = &buffer[end + 1..];
+                    continue;
+                } else {
+                    panic!("Record missing ';' separator");
+                }
+            }
+        };
+        let name = &line[..comma_seperator];
+        let raw_value = &line[comma_seperator + 1..];
+        stats.lines_read += 1;
+
+        if core::str::from_utf8(name).is_err() {
+            stats.record_failure(FailureKind::InvalidUtf8Name);
+            if lenient {
+                write_bad_record(quarantine, line);
+                buffer = &buffer[end + 1..];
+                continue;
+            } else {
+                panic!("Station name is not valid UTF-8");
+            }
+        }
+
+        let value: f64 = match fast_float::parse(raw_value) {
+            Ok(value) => value,
+            Err(_) => {
+                stats.record_failure(FailureKind::ParseError);
+                if lenient {
+                    write_bad_record(quarantine, line);
+                    buffer = &buffer[end + 1..];
+                    continue;
+                } else {
+                    panic!("Failed to parse value");
+                }
+            }
+        };
+
+        if !StationValues::in_range(value) {
+            stats.record_failure(FailureKind::OutOfRange);
+            if lenient {
+                write_bad_record(quarantine, line);
+                buffer = &buffer[end + 1..];
+                continue;
+            } else {
+                panic!("Value {} is out of the expected range", value);
+            }
+        }
+
+        on_record(name, value);
+        buffer = &buffer[end + 1..];
+    }
+
+    stats
+}
+
+/// Scans `data` and folds each record into `result`, owning the station name
+/// (`name.into()`) the first time it's seen. Used by callers whose chunks
+/// don't outlive a single call -- the streamed reader's per-read buffers --
+/// as well as any in-memory buffer handed in directly. `result` is left with
+/// unrounded min/max (callers that invoke this repeatedly over many chunks
+/// of the same map would otherwise pay a full rescan per call); round with
+/// [`round_off`] once the map is final.
+pub fn process_chunk(
+    data: &[u8],
+    result: &mut Map,
+    lenient: bool,
+    quarantine: Option<&dyn QuarantineSink>,
+) -> ParseStats {
+    scan_records(data, lenient, quarantine, |name, value| {
+        // Looked up by borrowed `name` first so repeat stations (the common
+        // case -- few distinct stations, many records) cost no allocation;
+        // `name.into()` only runs the one time a station is new.
+        if let Some(existing) = result.get_mut(name) {
+            fold_value(existing, value);
+        } else {
+            result.insert(name.into(), StationValues::new_with_value(value));
+        }
+    })
+}
+
+/// Borrowed-key result map: station name -> aggregated histogram, where the
+/// name is a `&[u8]` straight into the caller's buffer. Used by
+/// [`process_chunk_borrowed`] for callers whose data outlives the scan (a
+/// memory-mapped file held open for the whole run) so scanning never copies
+/// a station name, not even for a new station.
+pub type BorrowedMap<'a> = HashMap<&'a [u8], StationValues, FxBuildHasher>;
+
+/// Zero-copy counterpart to [`process_chunk`]: identical scanning and
+/// validation, but station names are kept as borrows into `data` instead of
+/// being copied into a `Box<[u8]>`. Only sound when `data` outlives `result`
+/// -- e.g. a `Mmap` kept alive for the whole run -- which is why this is a
+/// separate entry point rather than a flag on `process_chunk`.
+pub fn process_chunk_borrowed<'a>(
+    data: &'a [u8],
+    result: &mut BorrowedMap<'a>,
+    lenient: bool,
+    quarantine: Option<&dyn QuarantineSink>,
+) -> ParseStats {
+    scan_records(data, lenient, quarantine, |name, value| {
+        if let Some(existing) = result.get_mut(name) {
+            fold_value(existing, value);
+        } else {
+            result.insert(name, StationValues::new_with_value(value));
+        }
+    })
+}
+
+/// Folds `other` into `into`, widening min/max and summing histogram buckets
+/// per station. Used to combine the partial maps produced by independent
+/// workers (threads, streamed chunks) into one result.
+pub fn merge(into: &mut Map, other: Map) {
+    for (name, station_values) in other {
+        into.entry(name)
+            .and_modify(|e| e.merge(&station_values))
+            .or_insert(station_values);
+    }
+}
+
+/// Folds a [`BorrowedMap`] (e.g. one mmap-parallel worker's partial result)
+/// into an owned `Map`. This is the one point where a borrowed station name
+/// is ever copied -- once per distinct station per worker, not once per
+/// record -- which is what keeps [`process_chunk_borrowed`] zero-copy on the
+/// hot path.
+pub fn merge_borrowed(into: &mut Map, other: BorrowedMap<'_>) {
+    for (name, station_values) in other {
+        match into.get_mut(name) {
+            Some(existing) => existing.merge(&station_values),
+            None => {
+                into.insert(name.into(), station_values);
+            }
+        }
+    }
+}
@@ -1,12 +1,31 @@
 // Djimon Nowak
 
-use clap::Parser;
-use ordered_float::NotNan;
-use std::collections::{BTreeMap, HashMap};
+use clap::{Parser, ValueEnum};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::Mutex;
 use std::time::Instant;
-use rustc_hash::FxHashMap;
 use memmap2::Mmap;
 use memchr::memchr;
+use sci_comp_extra1::{
+    merge, merge_borrowed, process_chunk, process_chunk_borrowed, round_off, BorrowedMap, Map, ParseStats,
+    QuarantineSink,
+};
+
+const READ_BUF_SIZE: usize = 128 * 1024; // 128 KiB
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    // Keeps the file memory-mapped and splits it into N contiguous,
+    // newline-aligned sub-slices that worker threads process in place --
+    // zero-copy, no channel.
+    #[value(name = "mmap-parallel")]
+    MmapParallel,
+    // Reads the file (or any non-seekable stream) in bounded chunks and
+    // ships owned buffers to worker threads over a channel.
+    #[value(name = "streamed")]
+    Streamed,
+}
 
 #[derive(Parser, Debug)]
 #[command(
@@ -17,134 +36,219 @@ use memchr::memchr;
 struct Args {
     #[arg(short = 'f', long, help = "Path to the measurement file")]
     file: String,
+
+    #[arg(long, help = "Skip malformed/out-of-range records instead of panicking")]
+    lenient: bool,
+
+    #[arg(long = "report-bad", help = "Write quarantined raw records to this file (requires --lenient)")]
+    report_bad: Option<String>,
+
+    #[arg(long, value_delimiter = ',', help = "Comma-separated quantiles to report, e.g. 0.5,0.9,0.99,0.999")]
+    quantiles: Vec<f64>,
+
+    #[arg(long, help = "Linearly interpolate between adjacent buckets instead of snapping to one")]
+    interpolate_quantiles: bool,
+
+    #[arg(long, value_enum, default_value = "mmap-parallel", help = "Processing mode")]
+    mode: Mode,
+
+    #[arg(long, help = "Number of worker threads (defaults to available parallelism)")]
+    threads: Option<usize>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-struct StationValues {
-    min: f64,
-    max: f64,
-    frequency: HashMap<NotNan<f64>, u64>,
-    count: u64,
+// File-backed `QuarantineSink`: the only place in the binary that knows
+// quarantined records end up on disk. The library only sees the trait.
+struct FileQuarantine(Mutex<std::fs::File>);
+
+impl QuarantineSink for FileQuarantine {
+    fn record(&self, bad: &[u8]) {
+        let mut file = self.0.lock().unwrap();
+        let _ = file.write_all(bad);
+        let _ = file.write_all(b"\n");
+    }
 }
 
-impl StationValues {
-    fn new() -> Self {
-        StationValues {
-            min: 0.0,
-            max: 0.0,
-            count: 0,
-            frequency: (-999..=999)
-            .map(|x| (NotNan::new(round_off(x as f64 * 0.1)).unwrap(), 0)) // Convert to f64 and pair with 0
-            .collect()
-        }
+fn print_stats_summary(stats: &ParseStats) {
+    eprintln!(
+        "lines read: {}, lines skipped: {}",
+        stats.lines_read, stats.lines_skipped
+    );
+    for (kind, count) in &stats.failures {
+        eprintln!("  {}: {}", kind.label(), count);
     }
+}
 
-    fn new_with_value(value: f64) -> Self {
-        let mut station_values = StationValues::new();
-        station_values.min = value;
-        station_values.max = value;
-        *station_values.frequency.get_mut(&NotNan::new(value).expect("Value is NaN")).unwrap_or_else(|| panic!("Get mut failed with {}", value)) += 1;
-        station_values.count = 1;
-        station_values
+// Splits `data` into at most `n_threads` contiguous, non-overlapping slices,
+// snapping each split point forward to the next `\n` so no record is cut in
+// half. No bytes are copied -- every slice borrows directly from `data`.
+fn split_into_slices(data: &[u8], n_threads: usize) -> Vec<&[u8]> {
+    if n_threads <= 1 || data.is_empty() {
+        return vec![data];
     }
 
-    // get 0-indexed value by index
-    fn get_nth_value(&self, n: u64) -> f64 {
-        let mut cur = 0;
+    let mut boundaries = Vec::with_capacity(n_threads + 1);
+    boundaries.push(0usize);
+    for i in 1..n_threads {
+        let target = data.len() * i / n_threads;
+        let boundary = match memchr(b'\n', &data[target..]) {
+            Some(offset) => target + offset + 1,
+            None => data.len(),
+        };
+        boundaries.push(boundary.min(data.len()));
+    }
+    boundaries.push(data.len());
+    boundaries.dedup();
 
-        let keys: Vec<NotNan<f64>> = (-999..=999)
-        .map(|x| NotNan::new(round_off(x as f64 * 0.1)).unwrap())
-        .collect();
+    boundaries
+        .windows(2)
+        .map(|w| &data[w[0]..w[1]])
+        .filter(|slice| !slice.is_empty())
+        .collect()
+}
 
-        for key in keys {
-            let count = self.frequency[&key];
-            if n < cur + count {
-                return key.into_inner();
-            }
-            cur += count;
-        }
+// Zero-copy, work-stealing-free (but embarrassingly parallel) variant: each
+// scoped thread aggregates its own contiguous sub-slice of the mmap via the
+// library's `process_chunk_borrowed`, so station names stay borrows into the
+// mmap for the whole scan -- no allocation, no copy. The per-worker
+// `BorrowedMap`s are only ever converted to owned keys once, during the
+// final bucket-wise merge (once per distinct station per worker, not once
+// per record).
+fn calculate_station_values_parallel(
+    data: &[u8],
+    n_threads: usize,
+    lenient: bool,
+    quarantine: Option<&dyn QuarantineSink>,
+) -> (Map, ParseStats) {
+    let slices = split_into_slices(data, n_threads);
 
-        0.0
-    }
+    let mut result = Map::default();
+    let mut stats = ParseStats::default();
 
-    fn get_median(&self) -> f64 {
-        if self.count % 2 == 0 {
-            // even number of values -> return 1/2(left-middle + right-middle)
-            let left_mid_index = (self.count / 2) - 1;
-            (self.get_nth_value(left_mid_index) + self.get_nth_value(left_mid_index + 1)) / 2.0
-        } else {
-            // odd number of values -> return middle
-            self.get_nth_value(self.count / 2)
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = slices
+            .into_iter()
+            .map(|slice| {
+                scope.spawn(move || {
+                    let mut partial: BorrowedMap = BorrowedMap::default();
+                    let partial_stats = process_chunk_borrowed(slice, &mut partial, lenient, quarantine);
+                    (partial, partial_stats)
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let (partial, partial_stats) = handle.join().unwrap();
+            stats.merge(&partial_stats);
+            merge_borrowed(&mut result, partial);
         }
-    }
+    });
+
+    (result, stats)
 }
 
-// Calculate the station values
-fn calculate_station_values(data:&[u8]) -> FxHashMap<&[u8], StationValues> {
-    let mut result: FxHashMap<&[u8], StationValues> = FxHashMap::default();
-    let  mut buffer = data;
-    loop {
-        match memchr(b';', buffer) {
-            None => {
+// Reads `file` in bounded chunks and fans them out to worker threads over a
+// bounded channel, for inputs that can't be memory-mapped (pipes, sockets,
+// anything non-seekable). Each worker folds its chunks through
+// `process_chunk`, which shares its record parsing with the
+// `process_chunk_borrowed` the mmap-parallel path uses.
+fn calculate_station_values_streamed(
+    mut file: std::fs::File,
+    n_threads: usize,
+    lenient: bool,
+    quarantine: Option<&dyn QuarantineSink>,
+) -> (Map, ParseStats) {
+    // Same floor as `split_into_slices` effectively enforces for the
+    // mmap-parallel path: with zero consumer threads the reader would push
+    // chunks onto the bounded channel forever and block on `send` once it
+    // fills, with every row silently dropped in the meantime.
+    let n_threads = n_threads.max(1);
+    let (sender, receiver) = crossbeam_channel::bounded::<Box<[u8]>>(1_000);
+    let mut result = Map::default();
+    let mut stats = ParseStats::default();
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                scope.spawn(move || {
+                    let mut result = Map::default();
+                    let mut stats = ParseStats::default();
+                    for buf in receiver {
+                        let chunk_stats = process_chunk(&buf, &mut result, lenient, quarantine);
+                        stats.merge(&chunk_stats);
+                    }
+                    (result, stats)
+                })
+            })
+            .collect();
+
+        // Read the file in chunks and send the chunks to the processor threads
+        let mut buf = vec![0; READ_BUF_SIZE];
+        let mut bytes_not_processed = 0;
+        loop {
+            let bytes_read = file.read(&mut buf[bytes_not_processed..]).expect("Failed to read file");
+            if bytes_read == 0 {
                 break;
             }
-            Some(comma_seperator) => {
-                let end = memchr(b'\n', &buffer[comma_seperator..]).unwrap();
-                let name = &buffer[..comma_seperator];
-                let value = &buffer[comma_seperator+1..comma_seperator+end];
-                let value = fast_float::parse(value).expect("Failed to parse value");
-
-                result
-                    .entry(name)
-                    .and_modify(|e| {
-                        if value < e.min {
-                            e.min = value;
-                        }
-                        if value > e.max {
-                            e.max = value;
-                        }
-                        *e.frequency.get_mut(&NotNan::new(value).unwrap()).unwrap() += 1;
-                        e.count += 1;
-                    })
-                    .or_insert(StationValues::new_with_value(value));
-                buffer = &buffer[comma_seperator+end+1..];
-            }
 
-        }
-    }
+            let actual_buf = &mut buf[..bytes_not_processed + bytes_read];
+            let last_new_line_index = match find_new_line_pos(actual_buf) {
+                Some(index) => index,
+                None => {
+                    bytes_not_processed += bytes_read;
+                    if bytes_not_processed == buf.len() {
+                        panic!("No new line found in the read buffer");
+                    }
+                    continue; // try again, maybe the next read will have a newline
+                }
+            };
 
+            let buf_boxed = Box::<[u8]>::from(&actual_buf[..(last_new_line_index + 1)]);
+            sender.send(buf_boxed).expect("Failed to send buffer");
 
-    // Calculate the mean for all entries and round off to 1 decimal place
-    for (_, station_values) in result.iter_mut() {
-        station_values.min = round_off(station_values.min);
-        station_values.max = round_off(station_values.max);
-    }
+            actual_buf.copy_within(last_new_line_index + 1.., 0);
+            // You cannot use bytes_not_processed = bytes_read - last_new_line_index
+            // - 1; because the buffer will contain unprocessed bytes from the
+            // previous iteration and the new line index will be calculated from the
+            // start of the buffer
+            bytes_not_processed = actual_buf.len() - last_new_line_index - 1;
+        }
+        drop(sender);
+
+        for handle in handles {
+            let (map, thread_stats) = handle.join().unwrap();
+            stats.merge(&thread_stats);
+            merge(&mut result, map);
+        }
+    });
 
-    result
+    (result, stats)
 }
 
-fn round_off(value: f64) -> f64 {
-    (value * 10.0).round() / 10.0
+fn find_new_line_pos(bytes: &[u8]) -> Option<usize> {
+    // In this case (position is not far enough),
+    // naive version is faster than bstr (memchr)
+    bytes.iter().rposition(|&b| b == b'\n')
 }
 
-fn write_result_stdout(result: FxHashMap<&[u8], StationValues>) {
-    let mut ordered_result = BTreeMap::new();
-    for (station_name, station_values) in result {
-        ordered_result.insert(station_name, station_values);
-    }
-    let mut iterator = ordered_result.iter().peekable();
+fn write_result_stdout(result: BTreeMap<Vec<u8>, sci_comp_extra1::StationValues>, quantiles: &[f64], interpolate: bool) {
+    let mut iterator = result.iter().peekable();
     print!("{{");
     while let Some((station_name, station_values)) = iterator.next() {
+        let mut entry = format!(
+            "{}={:.1}/{:.1}/{:.1}",
+            std::str::from_utf8(station_name).expect("Unable to validate station name as UTF-8"),
+            station_values.min,
+            station_values.get_median(),
+            station_values.max
+        );
+        for q in quantiles {
+            entry.push_str(&format!("/{:.1}", station_values.get_quantile(*q, interpolate)));
+        }
         if iterator.peek().is_none() {
-            print!(
-                "{}={:.1}/{:.1}/{:.1}}}",
-                std::str::from_utf8(station_name).expect("Unable to validate station name as UTF-8"), station_values.min, station_values.get_median(), station_values.max
-            );
+            print!("{}}}", entry);
         } else {
-            print!(
-                "{}={:.1}/{:.1}/{:.1}, ",
-                std::str::from_utf8(station_name).expect("Unable to validate station name as UTF-8"), station_values.min, station_values.get_median(), station_values.max
-            );
+            print!("{}, ", entry);
         }
     }
 }
@@ -153,13 +257,108 @@ fn main() {
     let start = Instant::now();
     let args = Args::parse();
 
-    let file = std::fs::File::open(&args.file).expect("Failed to open file");
-    let mmap = unsafe { Mmap::map(&file).expect("Failed to map file") };
-    let data = &*mmap;
+    if args.report_bad.is_some() && !args.lenient {
+        eprintln!("--report-bad has no effect without --lenient");
+    }
+
+    let quarantine_file = args
+        .report_bad
+        .as_ref()
+        .map(|path| FileQuarantine(Mutex::new(std::fs::File::create(path).expect("Failed to create quarantine file"))));
+    let quarantine = quarantine_file.as_ref().map(|q| q as &dyn QuarantineSink);
+
+    let n_threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().unwrap().into());
 
-    let result = calculate_station_values(data);
-    write_result_stdout(result);
+    let (result, stats) = match args.mode {
+        Mode::MmapParallel => {
+            let file = std::fs::File::open(&args.file).expect("Failed to open file");
+            let mmap = unsafe { Mmap::map(&file).expect("Failed to map file") };
+            let data = &*mmap;
+            calculate_station_values_parallel(data, n_threads, args.lenient, quarantine)
+        }
+        Mode::Streamed => {
+            let file = std::fs::File::open(&args.file).expect("Failed to open file");
+            calculate_station_values_streamed(file, n_threads, args.lenient, quarantine)
+        }
+    };
+    // Rounded once here, over the final distinct-station count, rather than
+    // per worker or per chunk along the way.
+    let result: BTreeMap<Vec<u8>, sci_comp_extra1::StationValues> = result
+        .into_iter()
+        .map(|(name, mut v)| {
+            v.min = round_off(v.min);
+            v.max = round_off(v.max);
+            (name.into_vec(), v)
+        })
+        .collect();
+
+    write_result_stdout(result, &args.quantiles, args.interpolate_quantiles);
     let duration = start.elapsed();
     println!("\nTime taken is: {:?}", duration);
+    print_stats_summary(&stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn build_lines(values: &[f64]) -> String {
+        values.iter().map(|v| format!("weather;{}\n", v)).collect()
+    }
+
+    // The mmap-parallel path must reproduce the single-threaded median
+    // exactly, since splitting only changes how the histogram is built, not
+    // its contents after the merge.
+    #[test]
+    fn parallel_merge_matches_single_threaded_median() {
+        let values: Vec<f64> = (0..97).map(|i| (i as f64 * 0.3) - 12.0).collect();
+        let data = build_lines(&values);
+
+        let mut reference = Map::default();
+        process_chunk(data.as_bytes(), &mut reference, false, None);
+        let reference_median = reference.get(&b"weather"[..]).unwrap().get_median();
+
+        let (parallel, _) = calculate_station_values_parallel(data.as_bytes(), 4, false, None);
+        let parallel_median = parallel.get(&b"weather"[..]).unwrap().get_median();
+
+        assert_eq!(reference_median, parallel_median);
+    }
+
+    #[test]
+    fn split_into_slices_snaps_to_newlines_and_covers_input() {
+        let data = b"a;1\nb;2\nc;3\nd;4\n";
+        let slices = split_into_slices(data, 3);
+
+        let mut rejoined = Vec::new();
+        for slice in &slices {
+            assert!(slice.ends_with(b"\n"));
+            rejoined.extend_from_slice(slice);
+        }
+        assert_eq!(rejoined, data);
+    }
+
+    // `--threads 0` must not silently discard every row: with zero consumer
+    // threads spawned, the reader would push chunks onto the bounded channel
+    // forever and deadlock on `send` once it filled. Clamping to at least
+    // one thread keeps the streamed path from losing data or hanging.
+    #[test]
+    fn streamed_with_zero_threads_still_processes_every_record() {
+        let values: Vec<f64> = (0..50).map(|i| i as f64).collect();
+        let data = build_lines(&values);
+
+        let path = std::env::temp_dir().join(format!(
+            "sci_comp_extra1_streamed_zero_threads_{:?}.txt",
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, &data).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+
+        let (result, stats) = calculate_station_values_streamed(file, 0, false, None);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stats.lines_read, values.len() as u64);
+        assert_eq!(result.get(&b"weather"[..]).unwrap().count, values.len() as u64);
+    }
 }
@@ -0,0 +1,172 @@
+// Exercises the `sci_comp_extra1` library API directly on in-memory byte
+// buffers, with no file, mmap, or CLI involved -- this is what an embedder
+// (benchmark harness, no_std caller) would actually call.
+
+use sci_comp_extra1::{merge, process_chunk, Map, QuarantineSink, StationValues};
+use std::sync::Mutex;
+
+#[test]
+fn process_chunk_aggregates_min_max_median() {
+    let data = "a;1.0\na;2.0\na;3.0\n";
+    let mut result = Map::default();
+    process_chunk(data.as_bytes(), &mut result, false, None);
+
+    let station = result.get(&b"a"[..]).expect("station present");
+    assert_eq!(station.min, 1.0);
+    assert_eq!(station.max, 3.0);
+    assert_eq!(station.get_median(), 2.0);
+}
+
+#[test]
+fn merge_combines_partial_maps_bucket_wise() {
+    let mut a = Map::default();
+    process_chunk(b"a;1.0\na;2.0\n", &mut a, false, None);
+
+    let mut b = Map::default();
+    process_chunk(b"a;3.0\nb;5.0\n", &mut b, false, None);
+
+    merge(&mut a, b);
+
+    let station_a = a.get(&b"a"[..]).unwrap();
+    assert_eq!(station_a.count, 3);
+    assert_eq!(station_a.min, 1.0);
+    assert_eq!(station_a.max, 3.0);
+
+    let station_b = a.get(&b"b"[..]).unwrap();
+    assert_eq!(station_b.count, 1);
+}
+
+#[test]
+fn lenient_mode_skips_malformed_records() {
+    let mut result = Map::default();
+    let stats = process_chunk(b"a;1.0\na;notanumber\na;2.0\n", &mut result, true, None);
+
+    assert_eq!(stats.lines_read, 3);
+    assert_eq!(stats.lines_skipped, 1);
+    assert_eq!(result.get(&b"a"[..]).unwrap().count, 2);
+}
+
+// A minimal in-memory `QuarantineSink`, standing in for the binary's
+// file-backed one -- demonstrates that quarantining doesn't require a file.
+struct RecordingSink {
+    records: Mutex<Vec<Vec<u8>>>,
+}
+
+impl QuarantineSink for RecordingSink {
+    fn record(&self, bad: &[u8]) {
+        self.records.lock().unwrap().push(bad.to_vec());
+    }
+}
+
+#[test]
+fn lenient_mode_reports_quarantined_records_via_sink() {
+    let sink = RecordingSink {
+        records: Mutex::new(Vec::new()),
+    };
+    let mut result = Map::default();
+    process_chunk(b"a;1.0\na;notanumber\n", &mut result, true, Some(&sink));
+
+    let records = sink.records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0], b"a;notanumber");
+}
+
+// A line with no `;` must be quarantined on its own -- it must not absorb
+// the following line (and its newline) into what looks like one station
+// name spanning both lines.
+#[test]
+fn lenient_mode_quarantines_lines_missing_a_separator() {
+    let sink = RecordingSink {
+        records: Mutex::new(Vec::new()),
+    };
+    let mut result = Map::default();
+    let stats = process_chunk(b"badline\nstationB;5.0\n", &mut result, true, Some(&sink));
+
+    assert_eq!(stats.lines_skipped, 1);
+    assert!(result.get(&b"badline\nstationB"[..]).is_none());
+
+    let station = result.get(&b"stationB"[..]).expect("stationB present");
+    assert_eq!(station.count, 1);
+
+    let records = sink.records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0], b"badline");
+}
+
+#[test]
+fn lenient_mode_quarantines_non_utf8_station_names() {
+    let sink = RecordingSink {
+        records: Mutex::new(Vec::new()),
+    };
+    let mut result = Map::default();
+    let stats = process_chunk(b"bad\xFF;5.0\ngood;1.0\n", &mut result, true, Some(&sink));
+
+    assert_eq!(stats.lines_skipped, 1);
+    assert!(result.get(&b"bad\xFF"[..]).is_none());
+    assert_eq!(result.get(&b"good"[..]).unwrap().count, 1);
+
+    let records = sink.records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0], b"bad\xFF;5.0");
+}
+
+fn station_with_values(values: &[f64]) -> StationValues {
+    let mut result = Map::default();
+    let data: String = values.iter().map(|v| format!("s;{}\n", v)).collect();
+    process_chunk(data.as_bytes(), &mut result, false, None);
+    result.remove(&b"s"[..]).unwrap()
+}
+
+// count == 0 must not reach the `count / 2 - 1` arithmetic in the even
+// branch -- that underflows and panics in a debug build.
+#[test]
+fn get_median_on_empty_station_returns_zero() {
+    let station = StationValues::new();
+    assert_eq!(station.get_median(), 0.0);
+}
+
+#[test]
+fn get_quantile_snaps_to_a_bucket() {
+    // Ten evenly-spaced values: 0.0..=9.0
+    let station = station_with_values(&(0..10).map(|i| i as f64).collect::<Vec<_>>());
+
+    assert_eq!(station.get_quantile(0.0, false), 0.0);
+    assert_eq!(station.get_quantile(1.0, false), 9.0);
+    assert_eq!(station.get_quantile(0.5, false), 4.0);
+}
+
+#[test]
+fn get_quantile_interpolates_between_adjacent_buckets() {
+    let station = station_with_values(&(0..10).map(|i| i as f64).collect::<Vec<_>>());
+
+    // rank 4.5 sits exactly between buckets 4.0 and 5.0
+    assert_eq!(station.get_quantile(0.5, true), 4.5);
+    assert_eq!(station.get_quantile(0.0, true), 0.0);
+    assert_eq!(station.get_quantile(1.0, true), 9.0);
+}
+
+#[test]
+fn get_quantile_on_empty_station_returns_zero() {
+    let station = StationValues::new();
+    assert_eq!(station.get_quantile(0.5, false), 0.0);
+    assert_eq!(station.get_quantile(0.5, true), 0.0);
+}
+
+#[test]
+fn get_quantile_on_single_value_station_returns_that_value() {
+    let station = station_with_values(&[3.0]);
+    assert_eq!(station.get_quantile(0.0, false), 3.0);
+    assert_eq!(station.get_quantile(1.0, false), 3.0);
+    assert_eq!(station.get_quantile(0.0, true), 3.0);
+    assert_eq!(station.get_quantile(1.0, true), 3.0);
+}
+
+#[test]
+fn get_quantile_clamps_q_outside_zero_one() {
+    let station = station_with_values(&(0..10).map(|i| i as f64).collect::<Vec<_>>());
+
+    assert_eq!(station.get_quantile(-1.0, false), station.get_quantile(0.0, false));
+    assert_eq!(station.get_quantile(2.0, false), station.get_quantile(1.0, false));
+    assert_eq!(station.get_quantile(-1.0, true), station.get_quantile(0.0, true));
+    assert_eq!(station.get_quantile(2.0, true), station.get_quantile(1.0, true));
+}